@@ -1,6 +1,8 @@
 use std::fmt::Write;
 
-use social_store::{Post, State};
+use social_store::{
+    escape_html, FilterExpr, Notification, NotificationKind, Post, SortMode, State,
+};
 
 /// Just a HTML header
 const HEADER: &str = "<!DOCTYPE html>
@@ -34,22 +36,16 @@ pub struct PostView<'a> {
 
 impl<'a> ToHtml for PostView<'a> {
     fn to_html(&self, s: &mut String) -> std::fmt::Result {
-        let likers = self
-            .post
-            .likers()
-            .fold(String::new(), |s, liker| s + " \"" + liker + "\"");
-
-        let dislikers = self
-            .post
-            .dislikers()
-            .fold(String::new(), |s, disliker| s + " \"" + disliker + "\"");
-
-        let comments = self
-            .post
-            .comments()
-            .fold(String::new(), |s, (username, comment)| {
-                s + &format!("<li><b>@{username} says:</b> {comment}</li>")
-            });
+        let likers = self.post.likers().fold(String::new(), |s, liker| {
+            s + " \"" + &escape_html(liker) + "\""
+        });
+
+        let dislikers = self.post.dislikers().fold(String::new(), |s, disliker| {
+            s + " \"" + &escape_html(disliker) + "\""
+        });
+
+        let author = escape_html(self.author);
+        let comments = render_comments(self.post, self.post_id, &author, None);
 
         s.write_str(HEADER)?;
         write!(
@@ -94,37 +90,130 @@ impl<'a> ToHtml for PostView<'a> {
                 <h5><a href=\"/feed\">Back to Feed</h5>
             ",
             post_id = self.post_id,
-            author = self.author,
+            author = author,
             content = self.post.content,
         )?;
         s.write_str(FOOTER)
     }
 }
 
+/// Render the comments on `post` that reply to `parent` (`None` for top-level comments) as
+/// `<li>` entries, recursing into each comment's own replies as a nested `<ul>` so reply chains
+/// render indented under the comment they reply to
+fn render_comments(post: &Post, post_id: &u64, author: &str, parent: Option<u64>) -> String {
+    post.comments()
+        .filter(|(_, comment)| comment.parent == parent)
+        .fold(String::new(), |s, (comment_id, comment)| {
+            let replies = render_comments(post, post_id, author, Some(*comment_id));
+            s + &format!(
+                "<li><b>@{}</b> says: {}
+                    <ul>
+                        {replies}
+                        <li>
+                            <form action=\"/add-comment\" method=\"GET\">
+                                <input hidden name=\"post_username\" value=\"{author}\"/>
+                                <input hidden name=\"post_id\" value=\"{post_id}\"/>
+                                <input hidden name=\"parent_id\" value=\"{comment_id}\"/>
+                                <input name=\"username\" placeholder=\"Username\"/>
+                                <input name=\"comment\" placeholder=\"Your Reply\"/>
+                                <input type=\"submit\" value=\"Reply\"/>
+                            </form>
+                        </li>
+                    </ul>
+                </li>",
+                escape_html(&comment.author),
+                comment.content,
+            )
+        })
+}
+
 impl ToHtml for State {
     fn to_html(&self, s: &mut String) -> std::fmt::Result {
-        let posts = self
-            .posts()
+        FeedView {
+            state: self,
+            sort: SortMode::default(),
+            filter: None,
+        }
+        .to_html(s)
+    }
+}
+
+/// A view of the feed sorted according to `sort` and narrowed down by `filter`, rendered by
+/// `/feed` so users can switch ordering and query the timeline
+pub struct FeedView<'a> {
+    pub state: &'a State,
+    pub sort: SortMode,
+    pub filter: Option<&'a FilterExpr>,
+}
+
+impl<'a> ToHtml for FeedView<'a> {
+    fn to_html(&self, s: &mut String) -> std::fmt::Result {
+        s.write_str(HEADER)?;
+        s.write_str("<div id=\"posts\" style=\"border: solid 1px black;\">")?;
+        for (username, post, post_id) in self
+            .state
+            .sorted_posts(self.sort, self.filter)
+            .into_iter()
             .filter_map(|(username, post_id)| {
-                self.get_post(post_id).map(|post| (username, post, post_id))
+                self.state
+                    .get_post(post_id)
+                    .map(|post| (username, post, post_id))
             })
-            .fold(
-                String::from("<div id=\"posts\" style=\"border: solid 1px black;\">"),
-                |mut s, (username, post, post_id)| {
-                    let _ = write!(s, "<a href=\"/post/{}/{}\"><div>", username, post_id);
-                    let post = PostView {
-                        post_id,
-                        author: username,
-                        post,
-                    };
-                    let _ = post.to_html(&mut s);
-                    let _ = s.write_str("</div></a>");
-                    s
-                },
-            )
-            + "</div>";
+        {
+            write!(
+                s,
+                "<a href=\"/post/{}/{}\"><div>",
+                escape_html(username),
+                post_id
+            )?;
+            PostView {
+                post_id,
+                author: username,
+                post,
+            }
+            .to_html(s)?;
+            s.write_str("</div></a>")?;
+        }
+        s.write_str("</div>")?;
+        s.write_str(FOOTER)
+    }
+}
+
+/// A view of the notifications that were queued for a user, rendered by `/notifications/:username`
+pub struct NotificationsView<'a> {
+    pub state: &'a State,
+    pub notifications: &'a [Notification],
+}
+
+impl<'a> ToHtml for NotificationsView<'a> {
+    fn to_html(&self, s: &mut String) -> std::fmt::Result {
+        let items = self.notifications.iter().fold(String::new(), |s, n| {
+            let actor = escape_html(&n.actor);
+            let verb = match n.kind {
+                NotificationKind::Mentioned => "mentioned you in",
+                NotificationKind::Liked => "liked",
+                NotificationKind::Commented => "commented on",
+            };
+            let link = match self.state.author_of(&n.post_id) {
+                Some(author) => format!(
+                    "<a href=\"/post/{}/{}\">@{actor} {verb} post #{}</a>",
+                    escape_html(author),
+                    n.post_id,
+                    n.post_id
+                ),
+                None => format!("@{actor} {verb} post #{} (since deleted)", n.post_id),
+            };
+            s + "<li>" + &link + "</li>"
+        });
+
         s.write_str(HEADER)?;
-        s.write_str(&posts)?;
+        write!(
+            s,
+            "
+                <h2>Notifications</h2>
+                <ul>{items}</ul>
+            "
+        )?;
         s.write_str(FOOTER)
     }
 }
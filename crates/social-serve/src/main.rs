@@ -2,31 +2,90 @@ use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
     extract::{Path as AxumPath, Query as AxumQuery, State as AxumState},
-    response::{Html, Redirect},
-    routing::get,
+    http::StatusCode,
+    response::{Html, IntoResponse, Json, Redirect, Response},
+    routing::{get, post},
     Router,
 };
 use serde::Deserialize;
-use social_serve::{PostView, ToHtml};
-use social_store::State;
+use serde_json::{json, Value};
+use social_serve::{FeedView, NotificationsView, PostView, ToHtml};
+use social_store::{parse_filter, AsObject, Error, Inbox, SortMode, State};
 use tokio::{net::TcpListener, sync::RwLock};
 
 type SharedState = Arc<RwLock<State>>;
 
-async fn all_posts(AxumState(state): AxumState<SharedState>) -> Result<Html<String>, String> {
+/// Wraps `social_store::Error` so handlers can return it directly and have it turned into the
+/// right HTTP status code, instead of flattening every failure into a `500`
+struct AppError(Error);
+
+impl From<Error> for AppError {
+    fn from(err: Error) -> Self {
+        AppError(err)
+    }
+}
+
+impl From<std::fmt::Error> for AppError {
+    fn from(err: std::fmt::Error) -> Self {
+        AppError(Error::from(err))
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = match &self.0 {
+            Error::UserNotFound(_) | Error::PostNotFound(_) | Error::CommentNotFound(_) => {
+                StatusCode::NOT_FOUND
+            }
+            Error::UserAlreadyExists(_) => StatusCode::CONFLICT,
+            Error::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            Error::InvalidFederatedId(_) | Error::Federation(_) | Error::Render(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        (
+            status,
+            Html(format!(
+                "<!DOCTYPE html><html><body><h1>{status}</h1><p>{}</p></body></html>",
+                social_store::escape_html(&self.0.to_string())
+            )),
+        )
+            .into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct Feed {
+    #[serde(default)]
+    sort: SortMode,
+    #[serde(default)]
+    q: Option<String>,
+}
+
+async fn all_posts(
+    AxumState(state): AxumState<SharedState>,
+    AxumQuery(Feed { sort, q }): AxumQuery<Feed>,
+) -> Result<Html<String>, AppError> {
+    let filter = q
+        .as_deref()
+        .map(parse_filter)
+        .transpose()
+        .map_err(Error::from)?;
     let mut respone = String::new();
-    state
-        .read_owned()
-        .await
-        .to_html(&mut respone)
-        .map_err(|err| err.to_string())?;
+    let read_state = state.read_owned().await;
+    FeedView {
+        state: &read_state,
+        sort,
+        filter: filter.as_ref(),
+    }
+    .to_html(&mut respone)?;
     Ok(Html(respone))
 }
 
 async fn one_post(
     AxumState(state): AxumState<SharedState>,
     AxumPath((username, post_id)): AxumPath<(String, u64)>,
-) -> Result<Html<String>, String> {
+) -> Result<Html<String>, AppError> {
     let mut response = String::new();
     let read_state = state.read_owned().await;
     let post_view = PostView {
@@ -34,20 +93,47 @@ async fn one_post(
         author: &username,
         post: read_state
             .get_post(&post_id)
-            .ok_or("Post not found".to_string())?,
+            .ok_or(Error::PostNotFound(post_id))?,
     };
-    let _ = post_view.to_html(&mut response);
+    post_view.to_html(&mut response)?;
     Ok(Html(response))
 }
 
+/// Best-effort delivery of an outbound activity to every inbox following `username`, fired off
+/// in the background so handlers don't block the response on remote instances being slow or down
+fn deliver_to_followers(state: SharedState, username: String, activity: social_store::Activity) {
+    tokio::spawn(async move {
+        let inboxes: Vec<String> = state
+            .read_owned()
+            .await
+            .followers_of(&username)
+            .cloned()
+            .collect();
+        let client = reqwest::Client::new();
+        for inbox in inboxes {
+            if let Err(err) = client.post(&inbox).json(&activity).send().await {
+                eprintln!("failed to deliver activity to `{inbox}`: {err}");
+            }
+        }
+    });
+}
+
 async fn create_post(
     AxumState(state): AxumState<SharedState>,
     AxumPath((username, content)): AxumPath<(String, String)>,
-) -> Result<Redirect, String> {
-    let mut write_state = state.write_owned().await;
-    let new_post_id = write_state
-        .create_post(&username, content)
-        .map_err(|err| err.to_string())?;
+) -> Result<Redirect, AppError> {
+    let mut write_state = state.clone().write_owned().await;
+    let new_post_id = write_state.create_post(&username, content)?;
+    let post = write_state
+        .get_post(&new_post_id)
+        .expect("post was just created");
+    let activity = social_store::Activity {
+        kind: "Create".to_string(),
+        actor: write_state.full_id(&username),
+        object: post.as_object(&post.ap_id, &write_state.full_id(&username)),
+    };
+    drop(write_state);
+    deliver_to_followers(state, username.clone(), activity);
     Ok(Redirect::permanent(&format!(
         "/post/{username}/{new_post_id}"
     )))
@@ -56,11 +142,9 @@ async fn create_post(
 async fn register_user(
     AxumState(state): AxumState<SharedState>,
     AxumPath(username): AxumPath<String>,
-) -> Result<Redirect, String> {
+) -> Result<Redirect, AppError> {
     let mut write_state = state.write_owned().await;
-    write_state
-        .register_user(&username)
-        .map_err(|err| err.to_string())?;
+    write_state.register_user(&username)?;
     Ok(Redirect::permanent("/feed"))
 }
 
@@ -70,6 +154,8 @@ struct CreateComment {
     post_username: String,
     username: String,
     comment: String,
+    #[serde(default)]
+    parent_id: Option<u64>,
 }
 async fn create_comment(
     AxumState(state): AxumState<SharedState>,
@@ -78,13 +164,39 @@ async fn create_comment(
         post_username,
         username,
         comment,
+        parent_id,
     }): AxumQuery<CreateComment>,
-) -> Result<Redirect, String> {
-    state
-        .write_owned()
-        .await
-        .create_comment(post_id, &username, comment)
-        .map_err(|err| err.to_string())?;
+) -> Result<Redirect, AppError> {
+    let mut write_state = state.clone().write_owned().await;
+    let comment_id = write_state.create_comment(post_id, &username, comment.clone(), parent_id)?;
+    let post = write_state
+        .get_post(&post_id)
+        .expect("comment was just added to this post");
+    let comment_ap_id = post
+        .get_comment(&comment_id)
+        .expect("comment was just added to this post")
+        .ap_id
+        .clone();
+    let in_reply_to = match parent_id {
+        Some(parent_id) => post
+            .get_comment(&parent_id)
+            .expect("validated by create_comment")
+            .ap_id
+            .clone(),
+        None => post.ap_id.clone(),
+    };
+    let activity = social_store::Activity {
+        kind: "Create".to_string(),
+        actor: write_state.full_id(&username),
+        object: social_store::ActivityObject::Note {
+            id: comment_ap_id,
+            content: comment,
+            attributed_to: write_state.full_id(&username),
+            in_reply_to: Some(in_reply_to),
+        },
+    };
+    drop(write_state);
+    deliver_to_followers(state, username, activity);
     Ok(Redirect::permanent(&format!(
         "/post/{post_username}/{post_id}"
     )))
@@ -103,12 +215,21 @@ async fn like(
         username,
         post_username,
     }): AxumQuery<Like>,
-) -> Result<Redirect, String> {
-    let mut write_state = state.write_owned().await;
-    let post = write_state
-        .get_post_mut(&post_id)
-        .ok_or("Post not found".to_string())?;
-    post.like(&username);
+) -> Result<Redirect, AppError> {
+    let mut write_state = state.clone().write_owned().await;
+    write_state.like_post(post_id, &username)?;
+    let ap_id = write_state
+        .get_post(&post_id)
+        .expect("post was just liked")
+        .ap_id
+        .clone();
+    let activity = social_store::Activity {
+        kind: "Like".to_string(),
+        actor: write_state.full_id(&username),
+        object: social_store::ActivityObject::Id(ap_id),
+    };
+    drop(write_state);
+    deliver_to_followers(state, username, activity);
     Ok(Redirect::permanent(&format!(
         "/post/{post_username}/{post_id}"
     )))
@@ -121,11 +242,11 @@ async fn dislike(
         username,
         post_username,
     }): AxumQuery<Like>,
-) -> Result<Redirect, String> {
+) -> Result<Redirect, AppError> {
     let mut write_state = state.write_owned().await;
     let post = write_state
         .get_post_mut(&post_id)
-        .ok_or("Post not found".to_string())?;
+        .ok_or(Error::PostNotFound(post_id))?;
     post.dislike(&username);
     Ok(Redirect::permanent(&format!(
         "/post/{post_username}/{post_id}"
@@ -139,19 +260,87 @@ async fn unlike(
         username,
         post_username,
     }): AxumQuery<Like>,
-) -> Result<Redirect, String> {
+) -> Result<Redirect, AppError> {
     let mut write_state = state.write_owned().await;
     let post = write_state
         .get_post_mut(&post_id)
-        .ok_or("Post not found".to_string())?;
+        .ok_or(Error::PostNotFound(post_id))?;
     post.unlike(&username);
     Ok(Redirect::permanent(&format!(
         "/post/{post_username}/{post_id}"
     )))
 }
 
+/// The ActivityPub actor document for a local user, so remote instances can discover their
+/// inbox and outbox
+async fn actor(
+    AxumState(state): AxumState<SharedState>,
+    AxumPath(username): AxumPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let read_state = state.read_owned().await;
+    let _ = read_state
+        .get_user(&username)
+        .ok_or_else(|| Error::UserNotFound(username.clone()))?;
+    Ok(Json(json!({
+        "type": "Person",
+        "id": read_state.full_id(&username),
+        "inbox": "/inbox",
+        "outbox": format!("/outbox/{username}"),
+    })))
+}
+
+/// The activities a local user has produced, for remote instances to pull during discovery
+async fn outbox(
+    AxumState(state): AxumState<SharedState>,
+    AxumPath(username): AxumPath<String>,
+) -> Result<Json<Value>, AppError> {
+    let read_state = state.read_owned().await;
+    let _ = read_state
+        .get_user(&username)
+        .ok_or_else(|| Error::UserNotFound(username.clone()))?;
+    let activities: Vec<Value> = read_state
+        .posts()
+        .filter(|(author, _)| *author == &username)
+        .filter_map(|(author, post_id)| read_state.get_post(post_id).map(|post| (author, post)))
+        .map(|(author, post)| {
+            json!({
+                "type": "Create",
+                "actor": read_state.full_id(author),
+                "object": post.as_object(&post.ap_id, &read_state.full_id(author)),
+            })
+        })
+        .collect();
+    Ok(Json(json!(activities)))
+}
+
+/// The notifications queued for a user, e.g. `@mentions` and likes/comments on their posts
+async fn notifications(
+    AxumState(state): AxumState<SharedState>,
+    AxumPath(username): AxumPath<String>,
+) -> Result<Html<String>, AppError> {
+    let mut write_state = state.write_owned().await;
+    let _ = write_state
+        .get_user(&username)
+        .ok_or_else(|| Error::UserNotFound(username.clone()))?;
+    let notifications = write_state.take_notifications(&username);
+    let mut response = String::new();
+    NotificationsView {
+        state: &write_state,
+        notifications: &notifications,
+    }
+    .to_html(&mut response)?;
+    Ok(Html(response))
+}
+
+/// Receive an incoming ActivityPub activity from another instance and apply it to `State`
+async fn inbox(AxumState(state): AxumState<SharedState>, body: String) -> Result<(), AppError> {
+    let mut write_state = state.write_owned().await;
+    Inbox::with_defaults().dispatch(&mut write_state, &body)?;
+    Ok(())
+}
+
 #[tokio::main]
-async fn main() {
+async fn main() -> std::io::Result<()> {
     let app_state = State::new();
 
     let router = Router::new()
@@ -163,12 +352,16 @@ async fn main() {
         .route("/like", get(like))
         .route("/dislike", get(dislike))
         .route("/unlike", get(unlike))
+        .route("/notifications/:username", get(notifications))
+        .route("/users/:username", get(actor))
+        .route("/outbox/:username", get(outbox))
+        .route("/inbox", post(inbox))
         .route("/", get(|| async { Redirect::permanent("/feed") }));
 
     let app = router.with_state(Arc::new(RwLock::new(app_state)));
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 8000));
-    let listener = TcpListener::bind(&addr).await.unwrap();
+    let listener = TcpListener::bind(&addr).await?;
 
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app).await
 }
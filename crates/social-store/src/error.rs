@@ -0,0 +1,100 @@
+//! The crate-level error type returned by fallible `State` operations, so callers can match on
+//! what went wrong (and map it to an HTTP status code) instead of parsing a message string.
+
+use std::fmt;
+
+/// Everything that can go wrong operating on a `State`
+#[derive(Debug)]
+pub enum Error {
+    /// No user is registered under this username (or `user@host` identifier)
+    UserNotFound(String),
+    /// No post is registered under this id
+    PostNotFound(u64),
+    /// No comment is registered under this id on the post a reply was made to
+    CommentNotFound(u64),
+    /// A user is already registered under this username
+    UserAlreadyExists(String),
+    /// The supplied string isn't a `user@host` identifier
+    InvalidFederatedId(String),
+    /// An incoming federated activity was malformed, or no handler is registered for its kind
+    Federation(String),
+    /// A `/feed` filter query (`?q=`) failed to parse
+    InvalidQuery(String),
+    /// Rendering a `ToHtml` view failed
+    Render(fmt::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UserNotFound(username) => write!(f, "user `{username}` not registered"),
+            Error::PostNotFound(post_id) => write!(f, "post with id `{post_id}` doesn't exist"),
+            Error::CommentNotFound(comment_id) => {
+                write!(
+                    f,
+                    "comment with id `{comment_id}` doesn't exist on this post"
+                )
+            }
+            Error::UserAlreadyExists(username) => {
+                write!(f, "user `{username}` already registered")
+            }
+            Error::InvalidFederatedId(id) => write!(f, "`{id}` is not a `user@host` identifier"),
+            Error::Federation(message) => write!(f, "{message}"),
+            Error::InvalidQuery(message) => write!(f, "invalid filter query: {message}"),
+            Error::Render(err) => write!(f, "failed to render: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<fmt::Error> for Error {
+    fn from(err: fmt::Error) -> Self {
+        Error::Render(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Federation(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_messages_mention_the_offending_value() {
+        assert_eq!(
+            Error::UserNotFound("alice".to_string()).to_string(),
+            "user `alice` not registered"
+        );
+        assert_eq!(
+            Error::PostNotFound(42).to_string(),
+            "post with id `42` doesn't exist"
+        );
+        assert_eq!(
+            Error::CommentNotFound(7).to_string(),
+            "comment with id `7` doesn't exist on this post"
+        );
+        assert_eq!(
+            Error::UserAlreadyExists("alice".to_string()).to_string(),
+            "user `alice` already registered"
+        );
+        assert_eq!(
+            Error::InvalidFederatedId("alice".to_string()).to_string(),
+            "`alice` is not a `user@host` identifier"
+        );
+        assert_eq!(Error::Federation("boom".to_string()).to_string(), "boom");
+        assert_eq!(
+            Error::InvalidQuery("bad token".to_string()).to_string(),
+            "invalid filter query: bad token"
+        );
+    }
+
+    #[test]
+    fn fmt_error_converts_to_render() {
+        assert!(matches!(Error::from(fmt::Error), Error::Render(_)));
+    }
+}
@@ -0,0 +1,281 @@
+//! ActivityPub federation support: turning local mutations into outbound activities, and
+//! applying inbound activities from other instances to `State`.
+//!
+//! The shapes here are deliberately minimal versions of the ones Plume uses to drive its inbox:
+//! an `Activity` wraps an `actor` and an `object`, and an `Inbox` matches the incoming
+//! `(activity kind, object kind)` pair against registered handlers. The actor is not part of
+//! the match; every handler resolves it itself.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, State};
+
+/// The object embedded in an `Activity`, narrowed down to what this crate federates today
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ActivityObject {
+    /// A post or a comment; comments set `in_reply_to` to the AP id of their parent
+    Note {
+        id: String,
+        content: String,
+        attributed_to: String,
+        in_reply_to: Option<String>,
+    },
+    /// A reference to an object that already exists locally or remotely, by AP id, written as
+    /// the bare id string
+    #[serde(untagged)]
+    Id(String),
+}
+
+/// An incoming or outgoing ActivityPub activity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Activity {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The full `user@host` identifier of the actor performing the activity
+    pub actor: String,
+    pub object: ActivityObject,
+}
+
+/// Implemented by local types that know how to describe themselves as an ActivityPub object for
+/// outbound federation
+pub trait AsObject {
+    fn as_object(&self, ap_id: &str, attributed_to: &str) -> ActivityObject;
+}
+
+type Handler = Box<dyn Fn(&mut State, &Activity) -> Result<(), Error> + Send + Sync>;
+
+/// Dispatches incoming activities to whichever handler was registered for their
+/// `(activity kind, object kind)` combination, the way Plume's inbox routing works
+#[derive(Default)]
+pub struct Inbox {
+    handlers: HashMap<(&'static str, &'static str), Handler>,
+}
+
+impl Inbox {
+    pub fn new() -> Self {
+        Inbox {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Register a handler for a given `(activity kind, object kind)` combination, e.g.
+    /// `("Like", "Id")`
+    pub fn register(&mut self, activity: &'static str, object: &'static str, handler: Handler) {
+        let _ = self.handlers.insert((activity, object), handler);
+    }
+
+    /// Build an `Inbox` with the handlers this crate federates: posts, comments, likes and
+    /// undoing a like
+    pub fn with_defaults() -> Self {
+        let mut inbox = Inbox::new();
+
+        inbox.register(
+            "Create",
+            "Note",
+            Box::new(|state, activity| {
+                let ActivityObject::Note {
+                    content,
+                    attributed_to,
+                    in_reply_to,
+                    ..
+                } = &activity.object
+                else {
+                    return Err(Error::Federation("expected a Note object".to_string()));
+                };
+                let author = state.resolve_remote_author(attributed_to)?;
+                match in_reply_to {
+                    // a Note in reply to a known post is a top-level comment on it; an
+                    // `in_reply_to` naming a comment's AP id is not resolved and fails
+                    Some(parent_ap_id) => {
+                        let post_id = state.post_id_by_ap_id(parent_ap_id).ok_or_else(|| {
+                            Error::Federation(format!("unknown parent `{parent_ap_id}`"))
+                        })?;
+                        state.create_comment(post_id, &author, content.clone(), None)?;
+                    }
+                    // a bare Note is a new top-level post
+                    None => {
+                        let _ = state.create_remote_post(&author, content.clone())?;
+                    }
+                }
+                Ok(())
+            }),
+        );
+
+        inbox.register(
+            "Like",
+            "Id",
+            Box::new(|state, activity| {
+                let ActivityObject::Id(ap_id) = &activity.object else {
+                    return Err(Error::Federation("expected an Id object".to_string()));
+                };
+                let post_id = state
+                    .post_id_by_ap_id(ap_id)
+                    .ok_or_else(|| Error::Federation(format!("unknown post `{ap_id}`")))?;
+                let author = state.resolve_remote_author(&activity.actor)?;
+                state.like_post(post_id, &author)?;
+                Ok(())
+            }),
+        );
+
+        inbox.register(
+            "Undo",
+            "Id",
+            Box::new(|state, activity| {
+                let ActivityObject::Id(ap_id) = &activity.object else {
+                    return Err(Error::Federation("expected an Id object".to_string()));
+                };
+                let post_id = state
+                    .post_id_by_ap_id(ap_id)
+                    .ok_or_else(|| Error::Federation(format!("unknown post `{ap_id}`")))?;
+                let author = state.resolve_remote_author(&activity.actor)?;
+                state
+                    .get_post_mut(&post_id)
+                    .ok_or(Error::PostNotFound(post_id))?
+                    .unlike(&author);
+                Ok(())
+            }),
+        );
+
+        inbox
+    }
+
+    /// Parse the supplied JSON body as an `Activity` and apply it to `state` with the handler
+    /// registered for its `(activity, object)` kind
+    pub fn dispatch(&self, state: &mut State, body: &str) -> Result<(), Error> {
+        let activity: Activity = serde_json::from_str(body)?;
+        let object_kind = match &activity.object {
+            ActivityObject::Note { .. } => "Note",
+            ActivityObject::Id(_) => "Id",
+        };
+        let handler = self
+            .handlers
+            .get(&(activity.kind.as_str(), object_kind))
+            .ok_or_else(|| {
+                Error::Federation(format!(
+                    "no handler registered for `{}`/`{}`",
+                    activity.kind, object_kind
+                ))
+            })?;
+        handler(state, &activity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_with_post() -> (State, u64, String) {
+        let mut state = State::new();
+        state.register_user("alice").unwrap();
+        let post_id = state
+            .create_post("alice", "hello world".to_string())
+            .unwrap();
+        let ap_id = state.get_post(&post_id).unwrap().ap_id.clone();
+        (state, post_id, ap_id)
+    }
+
+    fn body_for(activity: &Activity) -> String {
+        serde_json::to_string(activity).unwrap()
+    }
+
+    #[test]
+    fn dispatch_creates_a_post_from_a_bare_note() {
+        let mut state = State::new();
+        state.register_user("alice").unwrap();
+        let activity = Activity {
+            kind: "Create".to_string(),
+            actor: "bob@remote.example".to_string(),
+            object: ActivityObject::Note {
+                id: "https://remote.example/1".to_string(),
+                content: "hi there".to_string(),
+                attributed_to: "bob@remote.example".to_string(),
+                in_reply_to: None,
+            },
+        };
+        Inbox::with_defaults()
+            .dispatch(&mut state, &body_for(&activity))
+            .unwrap();
+
+        let posts: Vec<_> = state.posts().collect();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].0, "bob@remote.example");
+    }
+
+    #[test]
+    fn dispatch_creates_a_comment_from_a_reply_note() {
+        let (mut state, post_id, ap_id) = state_with_post();
+        let activity = Activity {
+            kind: "Create".to_string(),
+            actor: "bob@remote.example".to_string(),
+            object: ActivityObject::Note {
+                id: "https://remote.example/1#comment".to_string(),
+                content: "nice post".to_string(),
+                attributed_to: "bob@remote.example".to_string(),
+                in_reply_to: Some(ap_id),
+            },
+        };
+        Inbox::with_defaults()
+            .dispatch(&mut state, &body_for(&activity))
+            .unwrap();
+
+        let post = state.get_post(&post_id).unwrap();
+        assert_eq!(post.comments().count(), 1);
+    }
+
+    #[test]
+    fn dispatch_likes_and_undoes_a_like() {
+        let (mut state, post_id, ap_id) = state_with_post();
+        let inbox = Inbox::with_defaults();
+
+        let like = Activity {
+            kind: "Like".to_string(),
+            actor: "bob@remote.example".to_string(),
+            object: ActivityObject::Id(ap_id.clone()),
+        };
+        inbox.dispatch(&mut state, &body_for(&like)).unwrap();
+        assert_eq!(
+            state.get_post(&post_id).unwrap().likers().count(),
+            1,
+            "the remote actor should now be a liker"
+        );
+
+        let undo = Activity {
+            kind: "Undo".to_string(),
+            actor: "bob@remote.example".to_string(),
+            object: ActivityObject::Id(ap_id),
+        };
+        inbox.dispatch(&mut state, &body_for(&undo)).unwrap();
+        assert_eq!(state.get_post(&post_id).unwrap().likers().count(), 0);
+    }
+
+    #[test]
+    fn dispatch_fails_for_unregistered_handler() {
+        let (mut state, _, ap_id) = state_with_post();
+        let activity = Activity {
+            kind: "Announce".to_string(),
+            actor: "bob@remote.example".to_string(),
+            object: ActivityObject::Id(ap_id),
+        };
+        let err = Inbox::with_defaults()
+            .dispatch(&mut state, &body_for(&activity))
+            .unwrap_err();
+        assert!(matches!(err, Error::Federation(_)));
+    }
+
+    #[test]
+    fn dispatch_fails_for_unknown_post() {
+        let mut state = State::new();
+        let activity = Activity {
+            kind: "Like".to_string(),
+            actor: "bob@remote.example".to_string(),
+            object: ActivityObject::Id("https://nowhere/1".to_string()),
+        };
+        let err = Inbox::with_defaults()
+            .dispatch(&mut state, &body_for(&activity))
+            .unwrap_err();
+        assert!(matches!(err, Error::Federation(_)));
+    }
+}
@@ -0,0 +1,494 @@
+//! A small query language for describing a feed, e.g. `author=alice and likes>3 and not
+//! disliked`, mirroring the generic-timeline filters Plume builds its feeds from.
+//!
+//! The pipeline is the usual three stages: [`tokenize`] turns the input into a token stream,
+//! [`parse`] runs a recursive-descent parser over those tokens into a [`FilterExpr`] tree, and
+//! [`FilterExpr::evaluate`] walks that tree against a single post.
+
+use std::fmt;
+
+use crate::{Post, State};
+
+/// An error parsing a filter expression, carrying the character offset it went wrong at so the
+/// caller can point the user at the offending part of their query
+#[derive(Debug)]
+pub struct ParseError {
+    pub pos: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at position {}: {}", self.pos, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for crate::Error {
+    fn from(err: ParseError) -> Self {
+        crate::Error::InvalidQuery(err.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Number(i64),
+    Eq,
+    Gt,
+    Lt,
+    LParen,
+    RParen,
+}
+
+struct Spanned {
+    token: Token,
+    pos: usize,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Spanned>, ParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '=' => {
+                tokens.push(Spanned {
+                    token: Token::Eq,
+                    pos: i,
+                });
+                i += 1;
+            }
+            '>' => {
+                tokens.push(Spanned {
+                    token: Token::Gt,
+                    pos: i,
+                });
+                i += 1;
+            }
+            '<' => {
+                tokens.push(Spanned {
+                    token: Token::Lt,
+                    pos: i,
+                });
+                i += 1;
+            }
+            '(' => {
+                tokens.push(Spanned {
+                    token: Token::LParen,
+                    pos: i,
+                });
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Spanned {
+                    token: Token::RParen,
+                    pos: i,
+                });
+                i += 1;
+            }
+            '"' => {
+                let start = i;
+                i += 1;
+                let mut s = String::new();
+                while i < chars.len() && chars[i] != '"' {
+                    s.push(chars[i]);
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return Err(ParseError {
+                        pos: start,
+                        message: "unterminated string literal".to_string(),
+                    });
+                }
+                i += 1; // closing quote
+                tokens.push(Spanned {
+                    token: Token::Str(s),
+                    pos: start,
+                });
+            }
+            c if c.is_ascii_digit()
+                || (c == '-' && chars.get(i + 1).is_some_and(char::is_ascii_digit)) =>
+            {
+                let start = i;
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let number: i64 =
+                    chars[start..i]
+                        .iter()
+                        .collect::<String>()
+                        .parse()
+                        .map_err(|_| ParseError {
+                            pos: start,
+                            message: "invalid number".to_string(),
+                        })?;
+                tokens.push(Spanned {
+                    token: Token::Number(number),
+                    pos: start,
+                });
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push(Spanned {
+                    token: Token::Ident(chars[start..i].iter().collect()),
+                    pos: start,
+                });
+            }
+            other => {
+                return Err(ParseError {
+                    pos: i,
+                    message: format!("unexpected character `{other}`"),
+                })
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// How a numeric predicate like `likes>3` compares against the post's actual count
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparator {
+    Gt,
+    Lt,
+    Eq,
+}
+
+impl Comparator {
+    fn holds(&self, actual: i64, expected: i64) -> bool {
+        match self {
+            Comparator::Gt => actual > expected,
+            Comparator::Lt => actual < expected,
+            Comparator::Eq => actual == expected,
+        }
+    }
+}
+
+/// The parsed form of a feed filter, evaluated per-post by `FilterExpr::evaluate`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterExpr {
+    Author(String),
+    Likes(Comparator, i64),
+    Dislikes(Comparator, i64),
+    Contains(String),
+    /// Whether the post has at least one dislike; combine with `not` to exclude disliked posts
+    Disliked,
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+}
+
+/// Parse a filter expression, e.g. `author=alice and (likes>3 or contains "hello")`
+pub fn parse(input: &str) -> Result<FilterExpr, ParseError> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.parse_or()?;
+    if let Some(tok) = parser.peek() {
+        return Err(ParseError {
+            pos: tok.pos,
+            message: "unexpected trailing input".to_string(),
+        });
+    }
+    Ok(expr)
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Spanned> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next_ident_is(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Spanned { token: Token::Ident(ident), .. }) if ident.eq_ignore_ascii_case(word))
+    }
+
+    fn advance(&mut self) -> Option<&Spanned> {
+        let tok = self.tokens.get(self.pos);
+        self.pos += 1;
+        tok
+    }
+
+    fn eof_error(&self, message: &str) -> ParseError {
+        ParseError {
+            pos: self.tokens.last().map(|t| t.pos + 1).unwrap_or(0),
+            message: message.to_string(),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<FilterExpr, ParseError> {
+        let mut lhs = self.parse_and()?;
+        while self.next_ident_is("or") {
+            self.pos += 1;
+            let rhs = self.parse_and()?;
+            lhs = FilterExpr::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<FilterExpr, ParseError> {
+        let mut lhs = self.parse_unary()?;
+        while self.next_ident_is("and") {
+            self.pos += 1;
+            let rhs = self.parse_unary()?;
+            lhs = FilterExpr::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<FilterExpr, ParseError> {
+        if self.next_ident_is("not") {
+            self.pos += 1;
+            return Ok(FilterExpr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<FilterExpr, ParseError> {
+        match self.advance() {
+            Some(Spanned {
+                token: Token::LParen,
+                ..
+            }) => {
+                let expr = self.parse_or()?;
+                match self.advance() {
+                    Some(Spanned {
+                        token: Token::RParen,
+                        ..
+                    }) => Ok(expr),
+                    Some(tok) => Err(ParseError {
+                        pos: tok.pos,
+                        message: "expected `)`".to_string(),
+                    }),
+                    None => Err(self.eof_error("expected `)`")),
+                }
+            }
+            Some(Spanned {
+                token: Token::Ident(ident),
+                pos,
+            }) => {
+                let pos = *pos;
+                let ident = ident.clone();
+                if ident.eq_ignore_ascii_case("contains") {
+                    match self.advance() {
+                        Some(Spanned {
+                            token: Token::Str(s),
+                            ..
+                        }) => Ok(FilterExpr::Contains(s.clone())),
+                        Some(tok) => Err(ParseError {
+                            pos: tok.pos,
+                            message: "expected a string after `contains`".to_string(),
+                        }),
+                        None => Err(self.eof_error("expected a string after `contains`")),
+                    }
+                } else if ident.eq_ignore_ascii_case("disliked") {
+                    Ok(FilterExpr::Disliked)
+                } else if ident.eq_ignore_ascii_case("author") {
+                    match self.advance() {
+                        Some(Spanned {
+                            token: Token::Eq, ..
+                        }) => {}
+                        Some(tok) => {
+                            return Err(ParseError {
+                                pos: tok.pos,
+                                message: "expected `=` after `author`".to_string(),
+                            })
+                        }
+                        None => return Err(self.eof_error("expected `=` after `author`")),
+                    }
+                    match self.advance() {
+                        Some(Spanned {
+                            token: Token::Ident(name),
+                            ..
+                        }) => Ok(FilterExpr::Author(name.clone())),
+                        Some(tok) => Err(ParseError {
+                            pos: tok.pos,
+                            message: "expected a username after `author=`".to_string(),
+                        }),
+                        None => Err(self.eof_error("expected a username after `author=`")),
+                    }
+                } else if ident.eq_ignore_ascii_case("likes")
+                    || ident.eq_ignore_ascii_case("dislikes")
+                {
+                    let comparator = match self.advance() {
+                        Some(Spanned {
+                            token: Token::Gt, ..
+                        }) => Comparator::Gt,
+                        Some(Spanned {
+                            token: Token::Lt, ..
+                        }) => Comparator::Lt,
+                        Some(Spanned {
+                            token: Token::Eq, ..
+                        }) => Comparator::Eq,
+                        Some(tok) => {
+                            return Err(ParseError {
+                                pos: tok.pos,
+                                message: format!("expected `>`, `<` or `=` after `{ident}`"),
+                            })
+                        }
+                        None => {
+                            return Err(
+                                self.eof_error(&format!("expected a comparator after `{ident}`"))
+                            )
+                        }
+                    };
+                    let number = match self.advance() {
+                        Some(Spanned {
+                            token: Token::Number(n),
+                            ..
+                        }) => *n,
+                        Some(tok) => {
+                            return Err(ParseError {
+                                pos: tok.pos,
+                                message: "expected a number".to_string(),
+                            })
+                        }
+                        None => return Err(self.eof_error("expected a number")),
+                    };
+                    if ident.eq_ignore_ascii_case("likes") {
+                        Ok(FilterExpr::Likes(comparator, number))
+                    } else {
+                        Ok(FilterExpr::Dislikes(comparator, number))
+                    }
+                } else {
+                    Err(ParseError {
+                        pos,
+                        message: format!("unknown predicate `{ident}`"),
+                    })
+                }
+            }
+            Some(tok) => Err(ParseError {
+                pos: tok.pos,
+                message: "expected a predicate, `not` or `(`".to_string(),
+            }),
+            None => Err(self.eof_error("expected a predicate, `not` or `(`")),
+        }
+    }
+}
+
+impl FilterExpr {
+    /// Evaluate this filter against the post identified by `post_id`, made by `author`
+    pub fn evaluate(&self, state: &State, author: &str, post_id: &u64, post: &Post) -> bool {
+        let _ = state;
+        let _ = post_id;
+        match self {
+            FilterExpr::Author(name) => author == name,
+            FilterExpr::Likes(cmp, n) => cmp.holds(post.likers().count() as i64, *n),
+            FilterExpr::Dislikes(cmp, n) => cmp.holds(post.dislikers().count() as i64, *n),
+            FilterExpr::Contains(needle) => post.content.as_raw().contains(needle.as_str()),
+            FilterExpr::Disliked => post.dislikers().count() > 0,
+            FilterExpr::And(a, b) => {
+                a.evaluate(state, author, post_id, post) && b.evaluate(state, author, post_id, post)
+            }
+            FilterExpr::Or(a, b) => {
+                a.evaluate(state, author, post_id, post) || b.evaluate(state, author, post_id, post)
+            }
+            FilterExpr::Not(a) => !a.evaluate(state, author, post_id, post),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+
+    #[test]
+    fn parses_simple_predicates() {
+        assert_eq!(
+            parse("author=alice").unwrap(),
+            FilterExpr::Author("alice".to_string())
+        );
+        assert_eq!(
+            parse("likes>3").unwrap(),
+            FilterExpr::Likes(Comparator::Gt, 3)
+        );
+        assert_eq!(
+            parse("dislikes<2").unwrap(),
+            FilterExpr::Dislikes(Comparator::Lt, 2)
+        );
+        assert_eq!(
+            parse("contains \"hello\"").unwrap(),
+            FilterExpr::Contains("hello".to_string())
+        );
+        assert_eq!(parse("disliked").unwrap(), FilterExpr::Disliked);
+    }
+
+    #[test]
+    fn parses_and_or_not_with_precedence_and_parens() {
+        // `and` binds tighter than `or`
+        assert_eq!(
+            parse("author=alice or likes>3 and disliked").unwrap(),
+            FilterExpr::Or(
+                Box::new(FilterExpr::Author("alice".to_string())),
+                Box::new(FilterExpr::And(
+                    Box::new(FilterExpr::Likes(Comparator::Gt, 3)),
+                    Box::new(FilterExpr::Disliked)
+                ))
+            )
+        );
+
+        assert_eq!(
+            parse("not (author=alice or disliked)").unwrap(),
+            FilterExpr::Not(Box::new(FilterExpr::Or(
+                Box::new(FilterExpr::Author("alice".to_string())),
+                Box::new(FilterExpr::Disliked)
+            )))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(parse("author").is_err());
+        assert!(parse("likes>>3").is_err());
+        assert!(parse("author=alice and").is_err());
+        assert!(parse("(author=alice").is_err());
+        assert!(parse("contains \"unterminated").is_err());
+        assert!(parse("unknown=alice").is_err());
+    }
+
+    #[test]
+    fn parse_error_maps_to_invalid_query() {
+        let err: crate::Error = parse("author").unwrap_err().into();
+        assert!(matches!(err, crate::Error::InvalidQuery(_)));
+    }
+
+    #[test]
+    fn evaluates_against_real_posts() {
+        let mut state = State::new();
+        state.register_user("alice").unwrap();
+        let post_id = state
+            .create_post("alice", "hello world".to_string())
+            .unwrap();
+        state.like_post(post_id, "alice").unwrap();
+
+        let post = state.get_post(&post_id).unwrap();
+
+        assert!(parse("author=alice")
+            .unwrap()
+            .evaluate(&state, "alice", &post_id, post));
+        assert!(!parse("author=bob")
+            .unwrap()
+            .evaluate(&state, "alice", &post_id, post));
+        assert!(parse("likes>0")
+            .unwrap()
+            .evaluate(&state, "alice", &post_id, post));
+        assert!(parse("contains \"world\"")
+            .unwrap()
+            .evaluate(&state, "alice", &post_id, post));
+        assert!(parse("not disliked")
+            .unwrap()
+            .evaluate(&state, "alice", &post_id, post));
+    }
+}
@@ -3,28 +3,64 @@ use std::{
     hash::{DefaultHasher, Hasher},
 };
 
-use anyhow::anyhow;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+
+mod error;
+pub use error::Error;
+
+mod federation;
+pub use federation::{Activity, ActivityObject, AsObject, Inbox};
+
+mod safe_string;
+pub use safe_string::{escape_html, SafeString};
+
+mod filter;
+pub use filter::{parse as parse_filter, Comparator, FilterExpr, ParseError};
+
+mod notifications;
+pub use notifications::{mentions, Notification, NotificationKind};
 
 /// A single Post made by a user that may have interactions (likes and dislikes) as well as comments
 /// by other (or the same) users
 #[derive(Debug)]
 pub struct Post {
-    /// The text content of the post
-    pub content: String,
+    /// The text content of the post, already HTML-escaped
+    pub content: SafeString,
     /// A map of whether the post has been liked or disliked by each user that has interacted (at
     /// least liked or disliked) with this post
     likes_and_dislikes: HashMap<String, bool>,
-    /// A map of the list of comments left by each user that commented on this post
-    comments: HashMap<String, Vec<String>>,
+    /// A map of the comments left on this post, keyed by a per-post comment id
+    comments: HashMap<u64, Comment>,
+    /// The id the next comment added to this post will be given
+    next_comment_id: u64,
+    /// The stable ActivityPub id this post is addressed by when federated to other instances
+    pub ap_id: String,
+    /// The time this post was created, used to order the feed
+    pub created_at: DateTime<Utc>,
+}
+
+/// A single comment on a `Post`, optionally in reply to another comment on the same post,
+/// mirroring the `in_response_to_id` model used for federated comments
+#[derive(Debug)]
+pub struct Comment {
+    pub author: String,
+    /// The text content of the comment, already HTML-escaped
+    pub content: SafeString,
+    /// The id of the comment this one replies to, or `None` for a top-level comment on the post
+    pub parent: Option<u64>,
+    /// The stable ActivityPub id this comment is addressed by when federated to other instances
+    pub ap_id: String,
 }
 
 impl Post {
-    pub fn new<S: Into<String>>(content: S) -> Post {
+    pub fn new<S: Into<String>>(content: S, ap_id: String, created_at: DateTime<Utc>) -> Post {
         Post {
-            content: content.into(),
+            content: SafeString::new(content.into()),
             likes_and_dislikes: HashMap::new(),
             comments: HashMap::new(),
+            next_comment_id: 0,
+            ap_id,
+            created_at,
         }
     }
 
@@ -47,16 +83,27 @@ impl Post {
         let _ = self.likes_and_dislikes.remove(author_username);
     }
 
-    /// Make the supplied username add the given comment on this post
-    pub fn add_comment(&mut self, author_username: &str, content: String) {
-        match self.comments.get_mut(author_username) {
-            Some(user_comments_this_post) => user_comments_this_post.push(content),
-            None => {
-                let _ = self
-                    .comments
-                    .insert(author_username.to_string(), Vec::from([content]));
-            }
-        }
+    /// Make the supplied username add the given comment on this post, optionally as a reply to
+    /// an existing comment, returning the id the new comment was stored under
+    pub fn add_comment(
+        &mut self,
+        author_username: &str,
+        content: String,
+        parent: Option<u64>,
+    ) -> u64 {
+        let comment_id = self.next_comment_id;
+        self.next_comment_id += 1;
+        let ap_id = format!("{}/comment/{comment_id}", self.ap_id);
+        let _ = self.comments.insert(
+            comment_id,
+            Comment {
+                author: author_username.to_string(),
+                content: SafeString::new(content),
+                parent,
+                ap_id,
+            },
+        );
+        comment_id
     }
 
     /// Return an iterator of the users that have liked this post
@@ -73,82 +120,281 @@ impl Post {
             .filter_map(|(username, liked_not_disliked)| (!liked_not_disliked).then_some(username))
     }
 
-    /// Return an iterator of the comments of this post along with the username of the user that
-    /// made the comment
-    pub fn comments(&self) -> impl Iterator<Item = (&String, &String)> {
-        self.comments.iter().flat_map(|(username, comments)| {
-            comments.iter().map(move |comment| (username, comment))
-        })
+    /// Return an iterator of the comments of this post, identified by their per-post comment id
+    pub fn comments(&self) -> impl Iterator<Item = (&u64, &Comment)> {
+        self.comments.iter()
+    }
+
+    /// Get an immutable reference to the comment identified by the supplied comment-id, if it
+    /// exists on this post
+    pub fn get_comment(&self, comment_id: &u64) -> Option<&Comment> {
+        self.comments.get(comment_id)
     }
 }
 
+/// A registered user, local or remote
+pub struct User {
+    /// The host this user was federated in from, or `None` for a user registered on this
+    /// instance
+    pub host: Option<String>,
+    /// The set of the post-ids for the posts made by this user
+    posts: HashSet<u64>,
+    /// The inbox URLs of the remote followers of this user, to be POSTed to when this user
+    /// creates a post, likes something or comments
+    followers: HashSet<String>,
+}
+
 /// The overall state of the social-media application containing the users and their posts
 pub struct State {
-    /// A map of the set of the post-ids for the posts made by each registered user on the platform
-    users: HashMap<String, HashSet<u64>>,
+    /// The host this instance of `social` is reachable at, used to build AP ids and actor URIs
+    host: String,
+    /// A map of the users registered on this platform, keyed by `username` for local users and
+    /// by the full `user@host` identifier for users resolved during federation
+    users: HashMap<String, User>,
     /// A map of the indexed collection of posts made by each user on the platform
     posts: HashMap<u64, Post>,
+    /// The time this `State` was created, used as the reference point for the `Hot` sort's
+    /// time-decay term
+    epoch: DateTime<Utc>,
+    /// A map of the notifications queued for each registered user
+    notifications: HashMap<String, Vec<Notification>>,
+}
+
+/// The order in which `State::sorted_posts` should return the feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortMode {
+    /// Reddit/Lemmy-style decayed popularity score, see `State::hot_score`
+    #[default]
+    Hot,
+    /// Most recently created first
+    New,
+    /// Highest `likes - dislikes` first
+    Top,
 }
 
 impl State {
     pub fn new() -> Self {
+        Self::new_with_host("localhost:8000".to_string())
+    }
+
+    /// Create a new, empty `State` for an instance reachable at the supplied host, used when
+    /// building AP ids for locally-created posts and the per-user actor document
+    pub fn new_with_host(host: String) -> Self {
         State {
+            host,
             users: HashMap::new(),
             posts: HashMap::new(),
+            epoch: Utc::now(),
+            notifications: HashMap::new(),
         }
     }
 
     /// Register a new user with the supplied username, failing if the username is already
     /// registered
-    pub fn register_user(&mut self, username: &str) -> Result<(), anyhow::Error> {
+    pub fn register_user(&mut self, username: &str) -> Result<(), Error> {
         if self.users.contains_key(username) {
-            Err(anyhow!("User `{}` already registered", username))
+            Err(Error::UserAlreadyExists(username.to_string()))
         } else {
-            let _ = self.users.insert(username.to_string(), HashSet::new());
+            let _ = self.users.insert(
+                username.to_string(),
+                User {
+                    host: None,
+                    posts: HashSet::new(),
+                    followers: HashSet::new(),
+                },
+            );
             Ok(())
         }
     }
 
+    /// Record that the supplied inbox URL now follows `username`, so future activities of
+    /// theirs are delivered there
+    pub fn add_follower(&mut self, username: &str, inbox_url: String) -> Result<(), Error> {
+        let _ = self
+            .users
+            .get_mut(username)
+            .ok_or_else(|| Error::UserNotFound(username.to_string()))?
+            .followers
+            .insert(inbox_url);
+        Ok(())
+    }
+
+    /// The inbox URLs of the followers of `username` that activities should be delivered to
+    pub fn followers_of(&self, username: &str) -> impl Iterator<Item = &String> {
+        self.users
+            .get(username)
+            .into_iter()
+            .flat_map(|user| user.followers.iter())
+    }
+
+    /// The full `user@host` identifier for a local user, as it should be addressed by other
+    /// instances
+    pub fn full_id(&self, username: &str) -> String {
+        format!("{username}@{}", self.host)
+    }
+
+    /// Resolve the author of an incoming federated activity to a local key in `users`,
+    /// registering them as a remote user keyed by their full `user@host` identifier if this is
+    /// the first activity seen from them
+    pub fn resolve_remote_author(&mut self, full_id: &str) -> Result<String, Error> {
+        let (_, host) = full_id
+            .split_once('@')
+            .ok_or_else(|| Error::InvalidFederatedId(full_id.to_string()))?;
+        if !self.users.contains_key(full_id) {
+            let _ = self.users.insert(
+                full_id.to_string(),
+                User {
+                    host: Some(host.to_string()),
+                    posts: HashSet::new(),
+                    followers: HashSet::new(),
+                },
+            );
+        }
+        Ok(full_id.to_string())
+    }
+
+    /// Find the id of the post addressed by the supplied AP id, if any
+    pub fn post_id_by_ap_id(&self, ap_id: &str) -> Option<u64> {
+        self.posts
+            .iter()
+            .find(|(_, post)| post.ap_id == ap_id)
+            .map(|(post_id, _)| *post_id)
+    }
+
     /// Make the supplied username create a new post with the supplied text content, returning the
     /// id of the newly created post
-    pub fn create_post(&mut self, username: &str, content: String) -> Result<u64, anyhow::Error> {
-        use std::hash::Hash;
-
-        let user_posts = self
+    pub fn create_post(&mut self, username: &str, content: String) -> Result<u64, Error> {
+        let user_posts = &mut self
             .users
             .get_mut(username)
-            .ok_or(anyhow!("user `{}` not registered", username))?;
-
-        // insert new post into the state posts
-        let new_post = Post::new(content);
-        let post_id = {
-            let mut hasher = DefaultHasher::new();
-            Utc::now().hash(&mut hasher);
-            hasher.finish()
-        };
-        self.posts.insert(post_id, new_post);
+            .ok_or_else(|| Error::UserNotFound(username.to_string()))?
+            .posts;
+
+        let post_id = Self::new_post_id();
+        let ap_id = format!("https://{}/post/{}/{}", self.host, username, post_id);
+        let mentioned: Vec<String> = mentions(&content).map(str::to_string).collect();
+        self.posts
+            .insert(post_id, Post::new(content, ap_id, Utc::now()));
 
         // insert new post's id to user's posts
         user_posts.insert(post_id);
+
+        for recipient in mentioned {
+            self.notify(&recipient, NotificationKind::Mentioned, username, post_id);
+        }
+        Ok(post_id)
+    }
+
+    /// Record a post that was created on a remote instance and federated to this one,
+    /// returning the id it was stored under
+    ///
+    /// `author` must already be a key in `users` (see [`State::resolve_remote_author`]).
+    pub fn create_remote_post(&mut self, author: &str, content: String) -> Result<u64, Error> {
+        let user_posts = &mut self
+            .users
+            .get_mut(author)
+            .ok_or_else(|| Error::UserNotFound(author.to_string()))?
+            .posts;
+
+        let post_id = Self::new_post_id();
+        let ap_id = format!("https://{author}/{post_id}");
+        self.posts
+            .insert(post_id, Post::new(content, ap_id, Utc::now()));
+        user_posts.insert(post_id);
         Ok(post_id)
     }
 
+    fn new_post_id() -> u64 {
+        use std::hash::Hash;
+
+        let mut hasher = DefaultHasher::new();
+        Utc::now().hash(&mut hasher);
+        hasher.finish()
+    }
+
     /// Make the supplied username create a new comment under the supplied post (identified by its
-    /// post-id) with the supplied comment content
+    /// post-id) with the supplied comment content, optionally as a reply to an existing comment
+    /// on that post, returning the id the new comment was stored under
     pub fn create_comment(
         &mut self,
         post_id: u64,
         author_username: &str,
         content: String,
-    ) -> Result<(), anyhow::Error> {
+        parent: Option<u64>,
+    ) -> Result<u64, Error> {
+        let mentioned: Vec<String> = mentions(&content).map(str::to_string).collect();
         let post = self
             .posts
             .get_mut(&post_id)
-            .ok_or(anyhow!("post with id `{}` doesn't exist", post_id))?;
-        post.add_comment(author_username, content);
+            .ok_or(Error::PostNotFound(post_id))?;
+        if let Some(parent_id) = parent {
+            if post.get_comment(&parent_id).is_none() {
+                return Err(Error::CommentNotFound(parent_id));
+            }
+        }
+        let comment_id = post.add_comment(author_username, content, parent);
+
+        if let Some(post_author) = self.author_of(&post_id).cloned() {
+            if post_author != author_username {
+                self.notify(
+                    &post_author,
+                    NotificationKind::Commented,
+                    author_username,
+                    post_id,
+                );
+            }
+        }
+        for recipient in mentioned {
+            self.notify(
+                &recipient,
+                NotificationKind::Mentioned,
+                author_username,
+                post_id,
+            );
+        }
+        Ok(comment_id)
+    }
+
+    /// Find the author of the post identified by `post_id`, if it exists
+    pub fn author_of(&self, post_id: &u64) -> Option<&String> {
+        self.posts()
+            .find(|(_, id)| *id == post_id)
+            .map(|(author, _)| author)
+    }
+
+    /// Make `liker` like the post identified by `post_id`, notifying its author
+    pub fn like_post(&mut self, post_id: u64, liker: &str) -> Result<(), Error> {
+        self.posts
+            .get_mut(&post_id)
+            .ok_or(Error::PostNotFound(post_id))?
+            .like(liker);
+
+        if let Some(post_author) = self.author_of(&post_id).cloned() {
+            if post_author != liker {
+                self.notify(&post_author, NotificationKind::Liked, liker, post_id);
+            }
+        }
         Ok(())
     }
 
+    /// Queue a notification for `recipient` if they're a registered user; silently does nothing
+    /// otherwise, since an `@mention` of a nonexistent user has nothing to notify
+    fn notify(&mut self, recipient: &str, kind: NotificationKind, actor: &str, post_id: u64) {
+        if self.users.contains_key(recipient) {
+            self.notifications
+                .entry(recipient.to_string())
+                .or_default()
+                .push(Notification::new(kind, actor.to_string(), post_id));
+        }
+    }
+
+    /// Drain and return the unread notifications queued for `username`, oldest first; once taken
+    /// they're gone, so viewing a user's notifications marks them as read
+    pub fn take_notifications(&mut self, username: &str) -> Vec<Notification> {
+        self.notifications.remove(username).unwrap_or_default()
+    }
+
     /// Get an immutable reference to the post identified by the supplied post-id, if it exists
     pub fn get_post(&self, post_id: &u64) -> Option<&Post> {
         self.posts.get(post_id)
@@ -162,14 +408,108 @@ impl State {
     /// Return an iterator over all posts on the platform (identified by their post-ids) along with
     /// the username of the user that posted it
     pub fn posts(&self) -> impl Iterator<Item = (&String, &u64)> {
-        self.users.iter().flat_map(|(username, post_ids)| {
-            post_ids.iter().map(move |post_id| (username, post_id))
-        })
+        self.users
+            .iter()
+            .flat_map(|(username, user)| user.posts.iter().map(move |post_id| (username, post_id)))
+    }
+
+    /// Get an immutable reference to the registered user with the supplied key (a bare username
+    /// for local users, or a full `user@host` identifier for remote ones)
+    pub fn get_user(&self, username: &str) -> Option<&User> {
+        self.users.get(username)
+    }
+
+    /// The Reddit/Lemmy-style "hot" score for a post: a log-scaled vote magnitude plus a
+    /// time-decay term centered on `self.epoch`
+    fn hot_score(&self, post: &Post) -> f64 {
+        const DECAY_SECONDS: f64 = 45_000.0;
+
+        let likes = post.likers().count() as i64;
+        let dislikes = post.dislikers().count() as i64;
+        let diff = likes - dislikes;
+        let order = (diff.unsigned_abs().max(1) as f64).log10();
+        let sign = match diff.cmp(&0) {
+            std::cmp::Ordering::Less => -1.0,
+            std::cmp::Ordering::Equal => 0.0,
+            std::cmp::Ordering::Greater => 1.0,
+        };
+        let created_secs = (post.created_at - self.epoch).num_seconds() as f64;
+        order + sign * created_secs / DECAY_SECONDS
+    }
+
+    /// Return the posts on the platform ordered according to `mode` and narrowed down by
+    /// `filter` (if any), going through `filtered_posts` so `/feed`'s sorting and filtering stay
+    /// on the same code path instead of drifting apart
+    pub fn sorted_posts(
+        &self,
+        mode: SortMode,
+        filter: Option<&FilterExpr>,
+    ) -> Vec<(&String, &u64)> {
+        let mut posts: Vec<(&String, &u64)> = match filter {
+            Some(filter) => self.filtered_posts(filter),
+            None => self.posts().collect(),
+        };
+        match mode {
+            SortMode::Hot => posts.sort_by(|(_, a), (_, b)| {
+                let (a, b) = (self.get_post(a), self.get_post(b));
+                match (a, b) {
+                    (Some(a), Some(b)) => self
+                        .hot_score(b)
+                        .partial_cmp(&self.hot_score(a))
+                        .unwrap_or(std::cmp::Ordering::Equal),
+                    _ => std::cmp::Ordering::Equal,
+                }
+            }),
+            SortMode::New => posts.sort_by(|(_, a), (_, b)| {
+                let (a, b) = (self.get_post(a), self.get_post(b));
+                match (a, b) {
+                    (Some(a), Some(b)) => b.created_at.cmp(&a.created_at),
+                    _ => std::cmp::Ordering::Equal,
+                }
+            }),
+            SortMode::Top => posts.sort_by(|(_, a), (_, b)| {
+                let (a, b) = (self.get_post(a), self.get_post(b));
+                match (a, b) {
+                    (Some(a), Some(b)) => {
+                        let score = |post: &Post| {
+                            post.likers().count() as i64 - post.dislikers().count() as i64
+                        };
+                        score(b).cmp(&score(a))
+                    }
+                    _ => std::cmp::Ordering::Equal,
+                }
+            }),
+        }
+        posts
+    }
+
+    /// Return the posts on the platform that match the supplied filter expression, in the same
+    /// order `posts()` would otherwise yield them
+    pub fn filtered_posts(&self, filter: &FilterExpr) -> Vec<(&String, &u64)> {
+        self.posts()
+            .filter(|(author, post_id)| {
+                self.get_post(post_id)
+                    .is_some_and(|post| filter.evaluate(self, author, post_id, post))
+            })
+            .collect()
+    }
+}
+
+impl AsObject for Post {
+    fn as_object(&self, ap_id: &str, attributed_to: &str) -> ActivityObject {
+        ActivityObject::Note {
+            id: ap_id.to_string(),
+            content: self.content.as_raw().to_string(),
+            attributed_to: attributed_to.to_string(),
+            in_reply_to: None,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use anyhow::anyhow;
+
     use super::*;
 
     #[test]
@@ -201,7 +541,7 @@ mod tests {
                 .ok_or(anyhow!("post not found"))?;
 
             // post content should match
-            assert_eq!(post.content, "This is my first post".to_string());
+            assert_eq!(post.content.as_raw(), "This is my first post");
 
             // make johndoe like the post
             post.like("johndoe");
@@ -226,36 +566,122 @@ mod tests {
             assert_eq!(dislikers, vec!["johndoe"]);
 
             // make johndoe make a comment on the post
-            post.add_comment("johndoe", "Nice Post!".to_string());
+            let first_comment_id = post.add_comment("johndoe", "Nice Post!".to_string(), None);
 
-            // there should be a single comment made by johndoe
-            let comments: HashSet<(&String, &String)> = post.comments().collect();
+            // there should be a single comment made by johndoe, with no parent
+            let comments: HashSet<(&String, &str)> = post
+                .comments()
+                .map(|(_, comment)| (&comment.author, comment.content.as_raw()))
+                .collect();
             assert_eq!(
                 comments,
-                HashSet::from([(&"johndoe".to_string(), &"Nice Post!".to_string())])
+                HashSet::from([(&"johndoe".to_string(), "Nice Post!")])
             );
 
-            // make johndoe make another comment on the post
-            post.add_comment("johndoe", "Reading this again...".to_string());
+            // make johndoe reply to their own comment
+            post.add_comment(
+                "johndoe",
+                "Reading this again...".to_string(),
+                Some(first_comment_id),
+            );
 
-            // there should now be two comments made by johndoe
-            let comments: HashSet<(&String, &String)> = post.comments().collect();
+            // there should now be two comments made by johndoe, the second one a reply to the
+            // first
+            let comments: HashSet<(&String, &str)> = post
+                .comments()
+                .map(|(_, comment)| (&comment.author, comment.content.as_raw()))
+                .collect();
             assert_eq!(
                 comments,
                 HashSet::from([
-                    (&"johndoe".to_string(), &"Nice Post!".to_string()),
-                    (&"johndoe".to_string(), &"Reading this again...".to_string())
+                    (&"johndoe".to_string(), "Nice Post!"),
+                    (&"johndoe".to_string(), "Reading this again...")
                 ])
             );
+            assert_eq!(post.get_comment(&first_comment_id).unwrap().parent, None);
         }
 
         // try creating comment from state rather than post
-        // this is bhavyakukkar's comment on his own post
+        // this is bhavyakukkar's comment on his own post, and it mentions johndoe
         state.create_comment(
             post_id,
             "bhavyakukkar",
             "Glad to hear that @johndoe".to_string(),
+            None,
         )?;
+
+        // johndoe should have been notified of the mention
+        let johndoe_notifications = state.take_notifications("johndoe");
+        assert_eq!(johndoe_notifications.len(), 1);
+        assert_eq!(johndoe_notifications[0].kind, NotificationKind::Mentioned);
+        assert_eq!(johndoe_notifications[0].actor, "bhavyakukkar");
+
+        // bhavyakukkar shouldn't be notified of their own comment on their own post
+        assert!(state.take_notifications("bhavyakukkar").is_empty());
+
+        // notifications are drained once taken
+        assert!(state.take_notifications("johndoe").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sorted_posts_orders_by_mode() -> Result<(), anyhow::Error> {
+        let mut state = State::new();
+        state.register_user("bhavyakukkar")?;
+
+        let first = state.create_post("bhavyakukkar", "first".to_string())?;
+        let second = state.create_post("bhavyakukkar", "second".to_string())?;
+
+        // `new` orders most recently created first
+        let new_order: Vec<u64> = state
+            .sorted_posts(SortMode::New, None)
+            .into_iter()
+            .map(|(_, id)| *id)
+            .collect();
+        assert_eq!(new_order, vec![second, first]);
+
+        // give `first` more (likes - dislikes) than `second`; two likes, since a single like
+        // scores log10(1) = 0 in `hot`, the same as no likes
+        state.like_post(first, "bhavyakukkar")?;
+        state.like_post(first, "johndoe")?;
+
+        // `top` orders by highest (likes - dislikes) first
+        let top_order: Vec<u64> = state
+            .sorted_posts(SortMode::Top, None)
+            .into_iter()
+            .map(|(_, id)| *id)
+            .collect();
+        assert_eq!(top_order, vec![first, second]);
+
+        // `hot` should also rank the liked post above the unliked, equally-recent one
+        let hot_order: Vec<u64> = state
+            .sorted_posts(SortMode::Hot, None)
+            .into_iter()
+            .map(|(_, id)| *id)
+            .collect();
+        assert_eq!(hot_order, vec![first, second]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sorted_posts_applies_the_filter_before_sorting() -> Result<(), anyhow::Error> {
+        let mut state = State::new();
+        state.register_user("bhavyakukkar")?;
+        state.register_user("johndoe")?;
+
+        let _ = state.create_post("bhavyakukkar", "first".to_string())?;
+        let second = state.create_post("johndoe", "second".to_string())?;
+
+        let filter = parse_filter("author=johndoe")?;
+        let filtered: Vec<u64> = state
+            .sorted_posts(SortMode::New, Some(&filter))
+            .into_iter()
+            .map(|(_, id)| *id)
+            .collect();
+        assert_eq!(filtered, vec![second]);
+
         Ok(())
     }
 }
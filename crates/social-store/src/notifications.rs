@@ -0,0 +1,47 @@
+//! Mentions and notifications: turning an `@username` in a post or comment into something the
+//! mentioned user can discover, the way Plume's comment handling records mentions.
+
+/// Scan `content` for `@username` mentions, yielding each mentioned username in order of
+/// appearance
+pub fn mentions(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .split('@')
+        .skip(1)
+        .map(|tail| {
+            tail.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .next()
+                .unwrap_or_default()
+        })
+        .filter(|username| !username.is_empty())
+}
+
+/// What kind of interaction a `Notification` is reporting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotificationKind {
+    /// The recipient was `@mentioned` in a post or comment
+    Mentioned,
+    /// The recipient's post was liked
+    Liked,
+    /// The recipient's post received a comment
+    Commented,
+}
+
+/// A single notification delivered to a user, recording who did what and on which post
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    /// The user whose action triggered this notification
+    pub actor: String,
+    /// The post the action happened on
+    pub post_id: u64,
+}
+
+impl Notification {
+    pub fn new(kind: NotificationKind, actor: String, post_id: u64) -> Self {
+        Notification {
+            kind,
+            actor,
+            post_id,
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! HTML-escaping for user-supplied text, so a post or comment containing `<script>` or other
+//! markup can't be rendered verbatim into the page.
+
+use std::fmt;
+
+/// HTML-escape `<`, `>`, `&`, `"` and `'` in the supplied text
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// User-supplied text that has been HTML-escaped once, at the point it entered `State`, so every
+/// later interpolation of it into HTML is safe by construction
+///
+/// `Display`ing a `SafeString` yields the escaped text; use [`SafeString::as_raw`] to get back
+/// the original text for federation or other non-HTML serialization.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SafeString {
+    raw: String,
+    escaped: String,
+}
+
+impl SafeString {
+    pub fn new<S: Into<String>>(raw: S) -> Self {
+        let raw = raw.into();
+        let escaped = escape_html(&raw);
+        SafeString { raw, escaped }
+    }
+
+    /// The original, unescaped text, for contexts that aren't HTML (e.g. serializing an
+    /// outbound ActivityPub activity)
+    pub fn as_raw(&self) -> &str {
+        &self.raw
+    }
+}
+
+impl fmt::Display for SafeString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.escaped)
+    }
+}
+
+impl<S: Into<String>> From<S> for SafeString {
+    fn from(raw: S) -> Self {
+        SafeString::new(raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_the_five_special_characters() {
+        assert_eq!(
+            escape_html(r#"<script>alert('hi & "bye"')</script>"#),
+            "&lt;script&gt;alert(&#x27;hi &amp; &quot;bye&quot;&#x27;)&lt;/script&gt;"
+        );
+        assert_eq!(escape_html("plain text"), "plain text");
+    }
+
+    #[test]
+    fn display_yields_the_escaped_text_while_as_raw_yields_the_original() {
+        let s = SafeString::new("<b>hi</b>");
+        assert_eq!(s.as_raw(), "<b>hi</b>");
+        assert_eq!(s.to_string(), "&lt;b&gt;hi&lt;/b&gt;");
+    }
+}